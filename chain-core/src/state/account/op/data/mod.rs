@@ -0,0 +1,3 @@
+#[cfg(feature = "new-txid")]
+pub mod psbt;
+pub mod withdraw;
@@ -0,0 +1,245 @@
+#![cfg(feature = "new-txid")]
+
+use crate::state::account::op::witness::StakedStateOpWitness;
+use crate::tx::data::attribute::TxAttributes;
+use crate::tx::witness::TxInWitness;
+use crate::tx::TaggedTransaction;
+use parity_scale_codec::{Decode, Encode, Error, Input, Output};
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::prelude::v1::Vec;
+
+/// errors produced while assembling, signing or finalizing a `PartialTransaction`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PartialTransactionError {
+    /// `finalize` was called before every required witness was attached
+    NotFullySigned,
+    /// `combine` was given two partial transactions for different underlying
+    /// transactions (or with a different number of inputs)
+    Mismatch,
+    /// `signer` was given an input index that is out of range for this transaction
+    InputIndexOutOfRange,
+}
+
+impl fmt::Display for PartialTransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartialTransactionError::NotFullySigned => {
+                write!(f, "not every required witness has been attached yet")
+            }
+            PartialTransactionError::Mismatch => {
+                write!(f, "partial transactions do not refer to the same transaction")
+            }
+            PartialTransactionError::InputIndexOutOfRange => {
+                write!(f, "input index is out of range for this transaction")
+            }
+        }
+    }
+}
+
+/// PSBT-style (BIP174) container for a transaction that is still being
+/// assembled/signed across parties, e.g. a watch-only constructor handing a
+/// `WithdrawUnbondedTx` off to an offline, air-gapped signer.
+///
+/// parties interact with it through the four BIP174 roles: `creator`,
+/// `updater`, `signer` and `finalizer`; independently-gathered witnesses can
+/// be merged back together with `combine`.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct PartialTransaction {
+    /// the transaction being assembled
+    pub transaction: TaggedTransaction,
+    /// witness collected so far for each UTXO-style input, in input order;
+    /// `None` where no signer has attached one yet
+    pub input_witnesses: Vec<Option<TxInWitness>>,
+    /// proof that the staked state / nonce referenced by a withdraw-style
+    /// transaction was authorized, once a signer has attached it
+    pub staked_state_witness: Option<StakedStateOpWitness>,
+    /// whether `transaction` needs a `staked_state_witness` to be considered
+    /// fully signed (e.g. `true` for a withdraw, `false` for a plain transfer)
+    pub requires_staked_state_witness: bool,
+    /// view-policy keys and other metadata carried alongside the transaction
+    pub attributes: TxAttributes,
+}
+
+impl Encode for PartialTransaction {
+    fn encode_to<EncOut: Output>(&self, dest: &mut EncOut) {
+        dest.push(&self.transaction);
+        dest.push(&self.input_witnesses);
+        dest.push(&self.staked_state_witness);
+        dest.push(&self.requires_staked_state_witness);
+        dest.push(&self.attributes);
+    }
+
+    fn size_hint(&self) -> usize {
+        self.transaction.size_hint()
+            + self.input_witnesses.size_hint()
+            + self.staked_state_witness.size_hint()
+            + self.requires_staked_state_witness.size_hint()
+            + self.attributes.size_hint()
+    }
+}
+
+impl Decode for PartialTransaction {
+    fn decode<DecIn: Input>(input: &mut DecIn) -> Result<Self, Error> {
+        let transaction = TaggedTransaction::decode(input)?;
+        let input_witnesses = Vec::decode(input)?;
+        let staked_state_witness = Option::decode(input)?;
+        let requires_staked_state_witness = bool::decode(input)?;
+        let attributes = TxAttributes::decode(input)?;
+
+        Ok(PartialTransaction {
+            transaction,
+            input_witnesses,
+            staked_state_witness,
+            requires_staked_state_witness,
+            attributes,
+        })
+    }
+}
+
+/// final, broadcastable form produced by `finalize`: the transaction bundled
+/// together with every witness that the `signer` role attached to it (a spend
+/// on this chain is not valid without its witnesses, so this -- not the bare
+/// `transaction` -- is what a finalizer hands off for broadcast)
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct FinalizedTransaction {
+    pub transaction: TaggedTransaction,
+    pub input_witnesses: Vec<TxInWitness>,
+    pub staked_state_witness: Option<StakedStateOpWitness>,
+}
+
+impl Encode for FinalizedTransaction {
+    fn encode_to<EncOut: Output>(&self, dest: &mut EncOut) {
+        dest.push(&self.transaction);
+        dest.push(&self.input_witnesses);
+        dest.push(&self.staked_state_witness);
+    }
+
+    fn size_hint(&self) -> usize {
+        self.transaction.size_hint()
+            + self.input_witnesses.size_hint()
+            + self.staked_state_witness.size_hint()
+    }
+}
+
+impl Decode for FinalizedTransaction {
+    fn decode<DecIn: Input>(input: &mut DecIn) -> Result<Self, Error> {
+        let transaction = TaggedTransaction::decode(input)?;
+        let input_witnesses = Vec::decode(input)?;
+        let staked_state_witness = Option::decode(input)?;
+
+        Ok(FinalizedTransaction {
+            transaction,
+            input_witnesses,
+            staked_state_witness,
+        })
+    }
+}
+
+impl PartialTransaction {
+    /// "Creator" role: begins a new partial transaction from an assembled but
+    /// as-yet-unsigned transaction, declaring how many UTXO-style inputs it
+    /// has and whether it needs a staked-state/nonce witness.
+    pub fn creator(
+        transaction: TaggedTransaction,
+        num_inputs: usize,
+        requires_staked_state_witness: bool,
+        attributes: TxAttributes,
+    ) -> Self {
+        PartialTransaction {
+            transaction,
+            input_witnesses: vec![None; num_inputs],
+            staked_state_witness: None,
+            requires_staked_state_witness,
+            attributes,
+        }
+    }
+
+    /// "Updater" role: replaces the metadata agreed upon by the parties
+    /// before signing begins.
+    pub fn updater(&mut self, attributes: TxAttributes) {
+        self.attributes = attributes;
+    }
+
+    /// "Signer" role: attaches a witness for a single UTXO-style input.
+    pub fn signer(
+        &mut self,
+        input_index: usize,
+        witness: TxInWitness,
+    ) -> Result<(), PartialTransactionError> {
+        match self.input_witnesses.get_mut(input_index) {
+            Some(slot) => {
+                *slot = Some(witness);
+                Ok(())
+            }
+            None => Err(PartialTransactionError::InputIndexOutOfRange),
+        }
+    }
+
+    /// "Signer" role for withdraw/deposit/unbond-style transactions: attaches
+    /// the staked-state/nonce proof rather than a per-input witness.
+    pub fn signer_staked_state(&mut self, witness: StakedStateOpWitness) {
+        self.staked_state_witness = Some(witness);
+    }
+
+    /// returns `true` once every required witness has been attached
+    pub fn is_fully_signed(&self) -> bool {
+        self.input_witnesses.iter().all(Option::is_some)
+            && (!self.requires_staked_state_witness || self.staked_state_witness.is_some())
+    }
+
+    /// "Finalizer" role: bundles the transaction together with every witness
+    /// that was collected, once every required witness has been attached.
+    pub fn finalize(&self) -> Result<FinalizedTransaction, PartialTransactionError> {
+        if !self.is_fully_signed() {
+            return Err(PartialTransactionError::NotFullySigned);
+        }
+
+        let input_witnesses = self
+            .input_witnesses
+            .iter()
+            .cloned()
+            .map(|witness| witness.expect("is_fully_signed checked every slot is Some"))
+            .collect();
+
+        Ok(FinalizedTransaction {
+            transaction: self.transaction.clone(),
+            input_witnesses,
+            staked_state_witness: self.staked_state_witness.clone(),
+        })
+    }
+
+    /// merges two partial transactions for the same underlying transaction by
+    /// unioning their collected witnesses -- e.g. recombining witnesses for
+    /// different inputs gathered independently by different signers. The union
+    /// is per input: where both carry a witness for the same input (or both
+    /// carry a staked-state witness), the one from `a` is kept and the one from
+    /// `b` is dropped; witnesses are never merged within a single input.
+    /// `requires_staked_state_witness` and `attributes` are taken from `a`.
+    pub fn combine(
+        a: PartialTransaction,
+        b: PartialTransaction,
+    ) -> Result<PartialTransaction, PartialTransactionError> {
+        if a.transaction != b.transaction || a.input_witnesses.len() != b.input_witnesses.len() {
+            return Err(PartialTransactionError::Mismatch);
+        }
+
+        let input_witnesses = a
+            .input_witnesses
+            .into_iter()
+            .zip(b.input_witnesses.into_iter())
+            .map(|(x, y)| x.or(y))
+            .collect();
+        let staked_state_witness = a.staked_state_witness.or(b.staked_state_witness);
+
+        Ok(PartialTransaction {
+            transaction: a.transaction,
+            input_witnesses,
+            staked_state_witness,
+            requires_staked_state_witness: a.requires_staked_state_witness,
+            attributes: a.attributes,
+        })
+    }
+}
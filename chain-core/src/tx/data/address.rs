@@ -12,19 +12,77 @@ use crate::init::address::{CroAddress, CroAddressError};
 
 use bech32::{self, u5, FromBase32, ToBase32};
 
+use blake2::{Blake2b512, Digest};
+
 use crate::init::network::{get_bech32_human_part_from_network, get_network, Network};
 
-type TreeRoot = H256;
+mod tree;
+
+pub use tree::{verify, MerkleProof, MerkleTree, XOnlyPubKey};
+
+pub(crate) type TreeRoot = H256;
+
+/// condition gating when an `ExtendedAddr::OrTreeWithTimelock` becomes spendable
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
+pub enum Timelock {
+    /// spendable once the chain reaches this block height
+    BlockHeight(u64),
+    /// spendable once the chain reaches this unix timestamp
+    Timestamp(u64),
+}
+
+impl Encode for Timelock {
+    fn encode_to<EncOut: Output>(&self, dest: &mut EncOut) {
+        match *self {
+            Timelock::BlockHeight(ref height) => {
+                dest.push_byte(0);
+                dest.push(height);
+            }
+            Timelock::Timestamp(ref time) => {
+                dest.push_byte(1);
+                dest.push(time);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> usize {
+        (match self {
+            Timelock::BlockHeight(ref height) => height.size_hint(),
+            Timelock::Timestamp(ref time) => time.size_hint(),
+        }) + 1
+    }
+}
+
+impl Decode for Timelock {
+    fn decode<DecIn: Input>(input: &mut DecIn) -> Result<Self, Error> {
+        let tag = input.read_byte()?;
+        match tag {
+            0 => Ok(Timelock::BlockHeight(Decode::decode(input)?)),
+            1 => Ok(Timelock::Timestamp(Decode::decode(input)?)),
+            _ => Err("No such variant in enum Timelock".into()),
+        }
+    }
+}
 
 /// MAST of Or operations (records the root).
 /// Root of a Merkle tree where leafs are X-only
 /// (potentially summed up / combined) pubkeys
+///
+/// the tree itself is built and proven against with `tree::MerkleTree`
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
 pub enum ExtendedAddr {
     /// ref: https://blockstream.com/2015/08/24/en-treesignatures/
     /// but each operation is "OR"
     /// (root of such tree)
     OrTree(TreeRoot),
+    /// same OR-tree as above, but the leaves may only be spent once `locktime`
+    /// has passed -- e.g. to gate a withdraw-style spend until after unbonding
+    OrTreeWithTimelock {
+        /// root of the OR-tree of spending conditions
+        root: TreeRoot,
+        /// height/time before which the address is not spendable
+        locktime: Timelock,
+    },
 }
 
 impl Encode for ExtendedAddr {
@@ -34,12 +92,23 @@ impl Encode for ExtendedAddr {
                 dest.push_byte(0);
                 dest.push(aa);
             }
+            ExtendedAddr::OrTreeWithTimelock {
+                ref root,
+                ref locktime,
+            } => {
+                dest.push_byte(1);
+                dest.push(root);
+                dest.push(locktime);
+            }
         }
     }
 
     fn size_hint(&self) -> usize {
         (match self {
             ExtendedAddr::OrTree(ref aa) => aa.size_hint(),
+            ExtendedAddr::OrTreeWithTimelock { root, locktime } => {
+                root.size_hint() + locktime.size_hint()
+            }
         }) + 1
     }
 }
@@ -47,29 +116,71 @@ impl Encode for ExtendedAddr {
 impl Decode for ExtendedAddr {
     fn decode<DecIn: Input>(input: &mut DecIn) -> Result<Self, Error> {
         let tag = input.read_byte()?;
-        // NOTE: tag 1 may be used for other address types -- e.g. one to denote
-        // requiring a different witness type (leaf may be a combination of root + timelock)
         match tag {
             0 => Ok(ExtendedAddr::OrTree({
                 let address: TreeRoot = Decode::decode(input)?;
                 address
             })),
+            1 => Ok(ExtendedAddr::OrTreeWithTimelock {
+                root: Decode::decode(input)?,
+                locktime: Decode::decode(input)?,
+            }),
             _ => Err("No such variant in enum ExtendedAddr".into()),
         }
     }
 }
 
+impl ExtendedAddr {
+    /// returns whether a withdraw-style spend of this address is currently
+    /// permitted: a plain `OrTree` has no lock and is always spendable, while
+    /// `OrTreeWithTimelock` requires the current height/time to have reached
+    /// the embedded lock condition
+    pub fn check_spendable(&self, current_height: u64, current_time: u64) -> bool {
+        match self {
+            ExtendedAddr::OrTree(_) => true,
+            ExtendedAddr::OrTreeWithTimelock { locktime, .. } => match locktime {
+                Timelock::BlockHeight(height) => current_height >= *height,
+                Timelock::Timestamp(time) => current_time >= *time,
+            },
+        }
+    }
+}
+
+/// tag prefixed to the locktime payload in the bech32 encoding of
+/// `OrTreeWithTimelock`, distinguishing a block height lock from a timestamp lock
+const LOCKTIME_HEIGHT_TAG: u8 = 0;
+const LOCKTIME_TIMESTAMP_TAG: u8 = 1;
+
+/// payload length (in bytes, before base32 expansion) of a plain `OrTree`:
+/// just the 32-byte root
+const OR_TREE_PAYLOAD_LEN: usize = 32;
+/// payload length of an `OrTreeWithTimelock`: the 32-byte root, a 1-byte
+/// locktime kind tag, and an 8-byte big-endian u64
+const OR_TREE_WITH_TIMELOCK_PAYLOAD_LEN: usize = 32 + 1 + 8;
+
 impl CroAddress<ExtendedAddr> for ExtendedAddr {
     fn to_cro(&self, network: Network) -> Result<String, CroAddressError> {
-        match self {
-            ExtendedAddr::OrTree(hash) => {
-                let checked_data: Vec<u5> = hash.to_vec().to_base32();
-                let encoded =
-                    bech32::encode(get_bech32_human_part_from_network(network), checked_data)
-                        .expect("bech32 encoding error");
-                Ok(encoded)
+        let payload: Vec<u8> = match self {
+            ExtendedAddr::OrTree(hash) => hash.to_vec(),
+            ExtendedAddr::OrTreeWithTimelock { root, locktime } => {
+                let mut payload = root.to_vec();
+                match locktime {
+                    Timelock::BlockHeight(height) => {
+                        payload.push(LOCKTIME_HEIGHT_TAG);
+                        payload.extend_from_slice(&height.to_be_bytes());
+                    }
+                    Timelock::Timestamp(time) => {
+                        payload.push(LOCKTIME_TIMESTAMP_TAG);
+                        payload.extend_from_slice(&time.to_be_bytes());
+                    }
+                }
+                payload
             }
-        }
+        };
+        let checked_data: Vec<u5> = payload.to_base32();
+        let encoded = bech32::encode(get_bech32_human_part_from_network(network), checked_data)
+            .expect("bech32 encoding error");
+        Ok(encoded)
     }
 
     fn from_cro(encoded_addr: &str, network: Network) -> Result<Self, CroAddressError> {
@@ -82,14 +193,161 @@ impl CroAddress<ExtendedAddr> for ExtendedAddr {
             .and_then(|decoded| {
                 Vec::from_base32(&decoded.1).map_err(|_e| CroAddressError::ConvertError)
             })
-            .map(|hash| {
-                let mut tree_root_hash: TreeRoot = [0 as u8; 32];
-                tree_root_hash.copy_from_slice(&hash.as_slice());
-                ExtendedAddr::OrTree(tree_root_hash)
+            .and_then(|payload| match payload.len() {
+                OR_TREE_PAYLOAD_LEN => {
+                    let mut tree_root_hash: TreeRoot = [0 as u8; 32];
+                    tree_root_hash.copy_from_slice(&payload);
+                    Ok(ExtendedAddr::OrTree(tree_root_hash))
+                }
+                OR_TREE_WITH_TIMELOCK_PAYLOAD_LEN => {
+                    let mut tree_root_hash: TreeRoot = [0 as u8; 32];
+                    tree_root_hash.copy_from_slice(&payload[..32]);
+                    let mut locktime_bytes = [0 as u8; 8];
+                    locktime_bytes.copy_from_slice(&payload[33..]);
+                    let locktime_value = u64::from_be_bytes(locktime_bytes);
+                    let locktime = match payload[32] {
+                        LOCKTIME_HEIGHT_TAG => Timelock::BlockHeight(locktime_value),
+                        LOCKTIME_TIMESTAMP_TAG => Timelock::Timestamp(locktime_value),
+                        _ => return Err(CroAddressError::ConvertError),
+                    };
+                    Ok(ExtendedAddr::OrTreeWithTimelock {
+                        root: tree_root_hash,
+                        locktime,
+                    })
+                }
+                _ => Err(CroAddressError::ConvertError),
             })
     }
 }
 
+/// context string mixed into the checksum preimage, as specified by SS58
+const SS58_PREFIX_CONTEXT: &[u8] = b"SS58PRE";
+
+/// one-byte SS58 address-type identifier for each network, reserved out of the
+/// range of common networks (Polkadot is 0, Kusama is 2, generic Substrate is 42)
+fn ss58_prefix(network: Network) -> u8 {
+    match network {
+        Network::Mainnet => 50,
+        Network::Testnet => 51,
+        Network::Devnet => 52,
+    }
+}
+
+fn ss58_network_from_prefix(prefix: u8) -> Option<Network> {
+    match prefix {
+        50 => Some(Network::Mainnet),
+        51 => Some(Network::Testnet),
+        52 => Some(Network::Devnet),
+        _ => None,
+    }
+}
+
+/// first two bytes of `Blake2b-512("SS58PRE" || prefixed_payload)`
+fn ss58_checksum(prefixed_payload: &[u8]) -> [u8; 2] {
+    let mut hasher = Blake2b512::new();
+    hasher.update(SS58_PREFIX_CONTEXT);
+    hasher.update(prefixed_payload);
+    let digest = hasher.finalize();
+    [digest[0], digest[1]]
+}
+
+/// SS58 (substrate/Polkadot-style) base58check address codec, offered
+/// alongside the bech32 `CroAddress` methods as an interop format for
+/// substrate/Polkadot tooling that expects SS58 rather than bech32
+pub trait Ss58Codec: Sized {
+    /// encodes as `base58(prefix_byte || payload || checksum)`
+    fn to_ss58(&self, network: Network) -> Result<String, CroAddressError>;
+    /// reverses `to_ss58`, verifying the checksum before reconstructing the address
+    fn from_ss58(encoded_addr: &str) -> Result<Self, CroAddressError>;
+}
+
+impl Ss58Codec for ExtendedAddr {
+    fn to_ss58(&self, network: Network) -> Result<String, CroAddressError> {
+        let root = match self {
+            ExtendedAddr::OrTree(root) => root,
+            // SS58 only carries a bare 32-byte payload, so it cannot also
+            // express the locktime of an `OrTreeWithTimelock`
+            ExtendedAddr::OrTreeWithTimelock { .. } => return Err(CroAddressError::ConvertError),
+        };
+
+        let mut prefixed_payload = vec![ss58_prefix(network)];
+        prefixed_payload.extend_from_slice(root);
+        let checksum = ss58_checksum(&prefixed_payload);
+
+        let mut full = prefixed_payload;
+        full.extend_from_slice(&checksum);
+        Ok(bs58::encode(full).into_string())
+    }
+
+    fn from_ss58(encoded_addr: &str) -> Result<Self, CroAddressError> {
+        let data = bs58::decode(encoded_addr)
+            .into_vec()
+            .map_err(|_e| CroAddressError::ConvertError)?;
+
+        if data.len() != 1 + OR_TREE_PAYLOAD_LEN + 2 {
+            return Err(CroAddressError::ConvertError);
+        }
+
+        let (prefixed_payload, checksum) = data.split_at(data.len() - 2);
+        if checksum != ss58_checksum(prefixed_payload) {
+            return Err(CroAddressError::ConvertError);
+        }
+
+        ss58_network_from_prefix(prefixed_payload[0]).ok_or(CroAddressError::InvalidNetwork)?;
+
+        let mut tree_root_hash: TreeRoot = [0 as u8; 32];
+        tree_root_hash.copy_from_slice(&prefixed_payload[1..]);
+        Ok(ExtendedAddr::OrTree(tree_root_hash))
+    }
+}
+
+/// every network whose bech32 prefix `from_cro_any` is able to detect
+const KNOWN_NETWORKS: &[Network] = &[Network::Mainnet, Network::Testnet, Network::Devnet];
+
+impl ExtendedAddr {
+    /// parses a bech32 address without requiring the caller to already know
+    /// which network it belongs to: the network is detected purely from the
+    /// decoded HRP (the part before the `1` separator), by matching it
+    /// against every known network prefix, so a mainnet address can always be
+    /// parsed regardless of any global network setting.
+    ///
+    /// matches against the HRP that bech32 itself decodes out, rather than a
+    /// raw `starts_with` on the encoded string, so a future prefix that
+    /// happens to be a string-prefix of another cannot be misclassified.
+    pub fn from_cro_any(encoded_addr: &str) -> Result<(Network, ExtendedAddr), CroAddressError> {
+        let (hrp, _data) =
+            bech32::decode(encoded_addr).map_err(|e| CroAddressError::Bech32Error(e.to_string()))?;
+
+        let network = KNOWN_NETWORKS
+            .iter()
+            .find(|network| get_bech32_human_part_from_network(**network) == hrp)
+            .copied()
+            .ok_or(CroAddressError::InvalidNetwork)?;
+
+        ExtendedAddr::from_cro(encoded_addr, network).map(|addr| (network, addr))
+    }
+}
+
+/// mirrors rust-bitcoin's `Address::from_str(..).require_network(net)`: chains
+/// network validation directly onto the result of `from_cro_any` instead of
+/// relying on a thread/process-global network setting
+pub trait RequireNetwork {
+    /// returns the address if it was parsed from `network`, an error otherwise
+    fn require_network(self, network: Network) -> Result<ExtendedAddr, CroAddressError>;
+}
+
+impl RequireNetwork for Result<(Network, ExtendedAddr), CroAddressError> {
+    fn require_network(self, network: Network) -> Result<ExtendedAddr, CroAddressError> {
+        self.and_then(|(parsed_network, addr)| {
+            if parsed_network == network {
+                Ok(addr)
+            } else {
+                Err(CroAddressError::InvalidNetwork)
+            }
+        })
+    }
+}
+
 impl fmt::Display for ExtendedAddr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.to_cro(get_network()).unwrap())
@@ -100,7 +358,7 @@ impl FromStr for ExtendedAddr {
     type Err = CroAddressError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        ExtendedAddr::from_cro(s, get_network()).map_err(|_e| CroAddressError::ConvertError)
+        ExtendedAddr::from_cro_any(s).map(|(_network, addr)| addr)
     }
 }
 
@@ -108,6 +366,15 @@ impl FromStr for ExtendedAddr {
 mod test {
     use super::*;
 
+    fn sample_root() -> TreeRoot {
+        let mut tree_root_hash = [0; 32];
+        tree_root_hash.copy_from_slice(
+            &hex::decode("0e7c045110b8dbf29765047380898919c5cb56f400112233445566778899aabb")
+                .unwrap(),
+        );
+        tree_root_hash
+    }
+
     #[test]
     fn should_be_correct_textual_address() {
         let network = Network::Devnet;
@@ -167,4 +434,133 @@ mod test {
             assert!(result.is_ok());
         }
     }
+
+    mod or_tree_with_timelock {
+        use super::*;
+
+        #[test]
+        fn should_round_trip_a_block_height_lock() {
+            let network = Network::Devnet;
+            let extended_addr = ExtendedAddr::OrTreeWithTimelock {
+                root: sample_root(),
+                locktime: Timelock::BlockHeight(123_456),
+            };
+
+            let bech32_addr = extended_addr.to_cro(network).unwrap();
+            let restored = ExtendedAddr::from_cro(&bech32_addr, network).unwrap();
+
+            assert_eq!(restored, extended_addr);
+        }
+
+        #[test]
+        fn should_round_trip_a_timestamp_lock() {
+            let network = Network::Devnet;
+            let extended_addr = ExtendedAddr::OrTreeWithTimelock {
+                root: sample_root(),
+                locktime: Timelock::Timestamp(1_600_000_000),
+            };
+
+            let bech32_addr = extended_addr.to_cro(network).unwrap();
+            let restored = ExtendedAddr::from_cro(&bech32_addr, network).unwrap();
+
+            assert_eq!(restored, extended_addr);
+        }
+
+        #[test]
+        fn should_not_be_spendable_before_the_lock_is_reached() {
+            let extended_addr = ExtendedAddr::OrTreeWithTimelock {
+                root: sample_root(),
+                locktime: Timelock::BlockHeight(100),
+            };
+
+            assert!(!extended_addr.check_spendable(99, 0));
+            assert!(extended_addr.check_spendable(100, 0));
+        }
+
+        #[test]
+        fn plain_or_tree_is_always_spendable() {
+            let extended_addr = ExtendedAddr::OrTree(sample_root());
+            assert!(extended_addr.check_spendable(0, 0));
+        }
+    }
+
+    mod from_cro_any {
+        use super::*;
+
+        const DEVNET_ADDR: &str =
+            "dcro1pe7qg5gshrdl99m9q3ecpzvfr8zuk4h5qqgjyv6y24n80zye42as88x8tg";
+
+        #[test]
+        fn should_detect_the_network_from_the_hrp() {
+            let (network, addr) = ExtendedAddr::from_cro_any(DEVNET_ADDR).unwrap();
+            assert_eq!(network, Network::Devnet);
+            assert_eq!(addr, ExtendedAddr::from_cro(DEVNET_ADDR, Network::Devnet).unwrap());
+        }
+
+        #[test]
+        fn should_parse_regardless_of_any_other_requested_network() {
+            // unlike the old `FromStr` (which relied on the global network),
+            // `from_cro_any` must succeed here even though `get_network()` may
+            // be configured for a different network in this process
+            assert!(ExtendedAddr::from_cro_any(DEVNET_ADDR).is_ok());
+        }
+
+        #[test]
+        fn require_network_should_accept_a_matching_network() {
+            let addr = ExtendedAddr::from_cro_any(DEVNET_ADDR).require_network(Network::Devnet);
+            assert!(addr.is_ok());
+        }
+
+        #[test]
+        fn require_network_should_reject_a_mismatched_network() {
+            let addr = ExtendedAddr::from_cro_any(DEVNET_ADDR).require_network(Network::Mainnet);
+            assert_eq!(addr.unwrap_err(), CroAddressError::InvalidNetwork);
+        }
+    }
+
+    mod ss58 {
+        use super::*;
+
+        #[test]
+        fn should_round_trip_an_or_tree_address() {
+            let extended_addr = ExtendedAddr::OrTree(sample_root());
+
+            let ss58_addr = extended_addr.to_ss58(Network::Devnet).unwrap();
+            let restored = ExtendedAddr::from_ss58(&ss58_addr).unwrap();
+
+            assert_eq!(restored, extended_addr);
+        }
+
+        #[test]
+        fn different_networks_should_produce_different_encodings() {
+            let extended_addr = ExtendedAddr::OrTree(sample_root());
+
+            let mainnet_addr = extended_addr.to_ss58(Network::Mainnet).unwrap();
+            let devnet_addr = extended_addr.to_ss58(Network::Devnet).unwrap();
+
+            assert_ne!(mainnet_addr, devnet_addr);
+        }
+
+        #[test]
+        fn should_reject_a_corrupted_checksum() {
+            let extended_addr = ExtendedAddr::OrTree(sample_root());
+            let ss58_addr = extended_addr.to_ss58(Network::Devnet).unwrap();
+            let mut data = bs58::decode(&ss58_addr).into_vec().unwrap();
+            let last = data.len() - 1;
+            data[last] ^= 0xff;
+            let corrupted_addr = bs58::encode(data).into_string();
+
+            assert!(ExtendedAddr::from_ss58(&corrupted_addr).is_err());
+        }
+
+        #[test]
+        fn should_not_support_the_timelocked_variant() {
+            let extended_addr = ExtendedAddr::OrTreeWithTimelock {
+                root: sample_root(),
+                locktime: Timelock::BlockHeight(1),
+            };
+
+            assert!(extended_addr.to_ss58(Network::Devnet).is_err());
+        }
+    }
 }
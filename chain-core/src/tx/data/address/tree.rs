@@ -0,0 +1,193 @@
+use parity_scale_codec::{Decode, Encode, Error, Input, Output};
+
+use serde::{Deserialize, Serialize};
+
+use blake2::{Blake2s256, Digest};
+
+use super::TreeRoot;
+
+use crate::common::H256;
+
+/// x-only (32-byte) public key used as a leaf of the OR-tree
+pub type XOnlyPubKey = H256;
+
+/// domain separator for leaf hashes, so a leaf can never collide with a branch
+const LEAF_TAG: &[u8] = b"leaf";
+/// domain separator for branch hashes, so a branch can never collide with a leaf
+const BRANCH_TAG: &[u8] = b"branch";
+
+fn tagged_hash(tag: &[u8], parts: &[&[u8]]) -> H256 {
+    let mut hasher = Blake2s256::new();
+    hasher.update(tag);
+    for part in parts {
+        hasher.update(part);
+    }
+    let digest = hasher.finalize();
+    let mut out: H256 = [0u8; 32];
+    out.copy_from_slice(&digest);
+    out
+}
+
+fn leaf_hash(pubkey: &XOnlyPubKey) -> H256 {
+    tagged_hash(LEAF_TAG, &[pubkey])
+}
+
+/// hashes two child nodes together, ordering them lexicographically first so that
+/// the same pair of children always produces the same branch hash regardless of
+/// which side of the tree they were built on (needed for canonical proofs)
+fn branch_hash(a: &H256, b: &H256) -> H256 {
+    if a <= b {
+        tagged_hash(BRANCH_TAG, &[a, b])
+    } else {
+        tagged_hash(BRANCH_TAG, &[b, a])
+    }
+}
+
+/// Merkle inclusion proof for a single leaf of an `OrTree` (MAST).
+///
+/// `path` holds the sibling hash at each level from the leaf up to (but not
+/// including) the root, so `verify` can recompute the root by repeatedly
+/// branch-hashing the accumulated value against each sibling in turn. A level
+/// where the node has no sibling (the last node of an odd-sized level, which
+/// is carried up unchanged) contributes no entry, so `path` can be shorter
+/// than the tree's depth.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub leaf: XOnlyPubKey,
+    pub path: Vec<H256>,
+}
+
+impl Encode for MerkleProof {
+    fn encode_to<EncOut: Output>(&self, dest: &mut EncOut) {
+        dest.push(&self.leaf);
+        dest.push(&self.path);
+    }
+
+    fn size_hint(&self) -> usize {
+        self.leaf.size_hint() + self.path.size_hint()
+    }
+}
+
+impl Decode for MerkleProof {
+    fn decode<DecIn: Input>(input: &mut DecIn) -> Result<Self, Error> {
+        let leaf: XOnlyPubKey = Decode::decode(input)?;
+        let path: Vec<H256> = Decode::decode(input)?;
+        Ok(MerkleProof { leaf, path })
+    }
+}
+
+/// recomputes the root implied by a `MerkleProof` and checks it against `root`
+pub fn verify(proof: &MerkleProof, root: &TreeRoot) -> bool {
+    let mut acc = leaf_hash(&proof.leaf);
+    for sibling in proof.path.iter() {
+        acc = branch_hash(&acc, sibling);
+    }
+    acc == *root
+}
+
+/// Builder for the MAST referenced by `ExtendedAddr::OrTree`: leaves are hashed
+/// with a domain-separated tag, then paired up level by level (lexicographically
+/// ordering each pair before hashing) until a single root remains.
+///
+/// ref: https://blockstream.com/2015/08/24/en-treesignatures/ -- but each
+/// operation is "OR", so proving any one leaf is sufficient to spend.
+pub struct MerkleTree {
+    leaves: Vec<XOnlyPubKey>,
+    /// `levels[0]` are the leaf hashes, each following level the parent hashes;
+    /// `levels.last()` is always `[root]`
+    levels: Vec<Vec<H256>>,
+}
+
+impl MerkleTree {
+    /// builds the tree bottom-up from its leaves; panics on an empty leaf set,
+    /// as an OR-tree with no spending conditions makes no sense
+    pub fn new(leaves: Vec<XOnlyPubKey>) -> Self {
+        assert!(!leaves.is_empty(), "OR-tree must have at least one leaf");
+        let mut levels = vec![leaves.iter().map(leaf_hash).collect::<Vec<H256>>()];
+        while levels.last().expect("levels is never empty").len() > 1 {
+            let prev = levels.last().expect("levels is never empty");
+            let next = prev
+                .chunks(2)
+                .map(|pair| {
+                    if pair.len() == 2 {
+                        branch_hash(&pair[0], &pair[1])
+                    } else {
+                        pair[0]
+                    }
+                })
+                .collect();
+            levels.push(next);
+        }
+        MerkleTree { leaves, levels }
+    }
+
+    /// root to be embedded in `ExtendedAddr::OrTree`
+    pub fn root(&self) -> TreeRoot {
+        self.levels.last().expect("levels is never empty")[0]
+    }
+
+    /// emits the sibling chain needed to prove `leaf_index` is part of the tree
+    pub fn prove(&self, leaf_index: usize) -> Option<MerkleProof> {
+        if leaf_index >= self.leaves.len() {
+            return None;
+        }
+        let mut index = leaf_index;
+        let mut path = Vec::new();
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling_index = index ^ 1;
+            if sibling_index < level.len() {
+                path.push(level[sibling_index]);
+            }
+            index /= 2;
+        }
+        Some(MerkleProof {
+            leaf: self.leaves[leaf_index],
+            path,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn leaf(byte: u8) -> XOnlyPubKey {
+        [byte; 32]
+    }
+
+    #[test]
+    fn should_prove_and_verify_every_leaf() {
+        let leaves = vec![leaf(1), leaf(2), leaf(3), leaf(4), leaf(5)];
+        let tree = MerkleTree::new(leaves.clone());
+        let root = tree.root();
+
+        for index in 0..leaves.len() {
+            let proof = tree.prove(index).expect("leaf index is in range");
+            assert_eq!(proof.leaf, leaves[index]);
+            assert!(verify(&proof, &root));
+        }
+    }
+
+    #[test]
+    fn should_reject_a_proof_against_the_wrong_root() {
+        let tree = MerkleTree::new(vec![leaf(1), leaf(2), leaf(3)]);
+        let other_root = MerkleTree::new(vec![leaf(9), leaf(8)]).root();
+        let proof = tree.prove(0).expect("leaf index is in range");
+
+        assert!(!verify(&proof, &other_root));
+    }
+
+    #[test]
+    fn should_return_none_for_an_out_of_range_leaf_index() {
+        let tree = MerkleTree::new(vec![leaf(1), leaf(2)]);
+        assert!(tree.prove(2).is_none());
+    }
+
+    #[test]
+    fn single_leaf_tree_root_is_its_leaf_hash() {
+        let tree = MerkleTree::new(vec![leaf(7)]);
+        let proof = tree.prove(0).expect("leaf index is in range");
+        assert!(proof.path.is_empty());
+        assert!(verify(&proof, &tree.root()));
+    }
+}